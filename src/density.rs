@@ -0,0 +1,49 @@
+use engine::alg::Vec2;
+
+use field2d::Field2D;
+
+// Population-density field sampled over a world-space grid, used as
+// the "global goals" driver for road growth: roads bend towards dense
+// cells, shorten there, and only branch where density clears a
+// threshold
+
+pub struct DensityField(Field2D);
+
+impl DensityField {
+    pub fn new(width: usize, height: usize, extent: Vec2) -> DensityField {
+        DensityField(Field2D::new(width, height, extent))
+    }
+
+    // Build a field from a grayscale image buffer, row-major,
+    // one byte per pixel
+    pub fn from_grayscale(
+        pixels: &[u8],
+        width: usize,
+        height: usize,
+        extent: Vec2,
+    ) -> DensityField {
+        DensityField(Field2D::from_grayscale(pixels, width, height, extent))
+    }
+
+    // Build a field from a radial falloff around the origin, roughly
+    // matching a city centered at (0, 0)
+    pub fn radial(width: usize, height: usize, extent: Vec2, falloff: f32) -> DensityField {
+        let mut field = Field2D::new(width, height, extent);
+
+        for y in 0..height {
+            for x in 0..width {
+                let point = field.cell_to_world(x, y);
+                let dist = point.mag();
+                field.cells[y * width + x] = (-dist * falloff).exp();
+            }
+        }
+
+        DensityField(field)
+    }
+
+    // Bilinearly sample the field at a world-space point; points
+    // outside the field's extent clamp to the nearest edge
+    pub fn sample(&self, point: Vec2) -> f32 {
+        self.0.bilinear_sample(point)
+    }
+}
@@ -0,0 +1,76 @@
+use engine::alg::Vec2;
+
+// Bilinearly-sampled 2D scalar grid over a world-space extent, shared
+// by `DensityField` and `HeightField` since both are just a sampled
+// grid with different semantics attached to the sampled value
+
+pub struct Field2D {
+    pub cells: Vec<f32>,
+    pub width: usize,
+    pub height: usize,
+
+    // World-space size covered by the field, centered on the origin
+    pub extent: Vec2,
+}
+
+impl Field2D {
+    pub fn new(width: usize, height: usize, extent: Vec2) -> Field2D {
+        Field2D {
+            cells: vec![0.0; width * height],
+            width,
+            height,
+            extent,
+        }
+    }
+
+    // Build a field from a grayscale image buffer, row-major,
+    // one byte per pixel
+    pub fn from_grayscale(
+        pixels: &[u8],
+        width: usize,
+        height: usize,
+        extent: Vec2,
+    ) -> Field2D {
+        debug_assert_eq!(pixels.len(), width * height);
+
+        let cells = pixels.iter()
+            .map(|&p| p as f32 / 255.0)
+            .collect();
+
+        Field2D { cells, width, height, extent }
+    }
+
+    pub fn cell_to_world(&self, x: usize, y: usize) -> Vec2 {
+        Vec2::new(
+            (x as f32 / (self.width - 1).max(1) as f32 - 0.5) * self.extent.x,
+            (y as f32 / (self.height - 1).max(1) as f32 - 0.5) * self.extent.y,
+        )
+    }
+
+    // Bilinearly sample the field at a world-space point; points
+    // outside the field's extent clamp to the nearest edge
+    pub fn bilinear_sample(&self, point: Vec2) -> f32 {
+        let u = (point.x / self.extent.x + 0.5)
+            .max(0.0).min(1.0) * (self.width - 1) as f32;
+        let v = (point.y / self.extent.y + 0.5)
+            .max(0.0).min(1.0) * (self.height - 1) as f32;
+
+        let x0 = u.floor() as usize;
+        let y0 = v.floor() as usize;
+        let x1 = (x0 + 1).min(self.width - 1);
+        let y1 = (y0 + 1).min(self.height - 1);
+
+        let tx = u - x0 as f32;
+        let ty = v - y0 as f32;
+
+        let c00 = self.cells[y0 * self.width + x0];
+        let c10 = self.cells[y0 * self.width + x1];
+        let c01 = self.cells[y1 * self.width + x0];
+        let c11 = self.cells[y1 * self.width + x1];
+
+        let top = c00 + (c10 - c00) * tx;
+        let bottom = c01 + (c11 - c01) * tx;
+
+        top + (bottom - top) * ty
+    }
+}
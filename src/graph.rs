@@ -0,0 +1,305 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use engine::alg::{Vec2, Vec3};
+use engine::graphics::Line;
+
+// Smallest distance between two vertices before they're considered
+// the same point
+const VERTEX_EPSILON: f32 = 0.0001;
+
+/* Vertex/edge representation of the generated road network, replacing
+   a flat Vec<Line> so that merges (snaps, intersections) share real
+   vertices instead of producing disconnected, overlapping segments */
+
+#[derive(Default)]
+pub struct RoadGraph {
+    pub vertices: Vec<Vec3>,
+    pub edges: Vec<(usize, usize)>,
+}
+
+// Result of `RoadGraph::connect`: the inserted line plus the vertex
+// indices at either end
+pub struct Connected {
+    pub line: Line,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl RoadGraph {
+    pub fn with_capacity(capacity: usize) -> RoadGraph {
+        RoadGraph {
+            vertices: Vec::with_capacity(capacity),
+            edges: Vec::with_capacity(capacity),
+        }
+    }
+
+    // Find the nearest existing vertex within radius, if any
+    pub fn nearest_vertex(
+        &self,
+        point: Vec3,
+        radius: f32,
+    ) -> Option<usize> {
+        self.nearest_vertex_among(
+            (0..self.vertices.len()), point, radius,
+        )
+    }
+
+    // As `nearest_vertex`, but restricted to a candidate set of
+    // indices (e.g. the handful a broad-phase grid narrowed down to)
+    pub fn nearest_vertex_among(
+        &self,
+        indices: impl IntoIterator<Item = usize>,
+        point: Vec3,
+        radius: f32,
+    ) -> Option<usize> {
+        let mut nearest = None;
+        let mut nearest_dist = radius;
+
+        for i in indices {
+            let dist = (self.vertices[i] - point).mag();
+            if dist <= nearest_dist {
+                nearest = Some(i);
+                nearest_dist = dist;
+            }
+        }
+
+        nearest
+    }
+
+    // Look up the index for a point, inserting a new vertex if none
+    // already exists at (approximately) that position
+    pub fn vertex_for(&mut self, point: Vec3) -> usize {
+        if let Some(i) = self.nearest_vertex(point, VERTEX_EPSILON) {
+            return i;
+        }
+
+        self.vertices.push(point);
+        self.vertices.len() - 1
+    }
+
+    // Insert an edge between two points, reusing or creating vertices
+    // as needed, and return the resulting line along with the vertex
+    // indices it connects (so callers can e.g. split another edge at
+    // one of them)
+    pub fn connect(&mut self, start: Vec3, end: Vec3) -> Connected {
+        let a = self.vertex_for(start);
+        let b = self.vertex_for(end);
+        self.edges.push((a, b));
+
+        Connected {
+            line: Line::new(self.vertices[a], self.vertices[b]),
+            start: a,
+            end: b,
+        }
+    }
+
+    // Split an existing edge at `vertex`, replacing it with two edges
+    // running from each of its original endpoints to `vertex`. Used
+    // when a new segment crosses an existing edge mid-span, so the
+    // crossing point becomes a real intersection instead of a
+    // degree-one dead end. Returns the indices of both sub-edges.
+    pub fn split_edge(&mut self, edge_index: usize, vertex: usize) -> (usize, usize) {
+        let (a, b) = self.edges[edge_index];
+        self.edges[edge_index] = (a, vertex);
+        self.edges.push((vertex, b));
+
+        (edge_index, self.edges.len() - 1)
+    }
+
+    pub fn line(&self, edge: (usize, usize)) -> Line {
+        Line::new(self.vertices[edge.0], self.vertices[edge.1])
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = Line> + '_ {
+        self.edges.iter().map(move |&edge| self.line(edge))
+    }
+
+    pub fn len(&self) -> usize {
+        self.edges.len()
+    }
+
+    fn neighbors(&self, vertex: usize) -> impl Iterator<Item = usize> + '_ {
+        self.edges.iter().filter_map(move |&(a, b)| {
+            if a == vertex { Some(b) }
+            else if b == vertex { Some(a) }
+            else { None }
+        })
+    }
+
+    fn nearest_vertex_2d(&self, point: Vec2) -> Option<usize> {
+        self.vertices.iter()
+            .map(|v| Vec2::new(v.x, v.z))
+            .enumerate()
+            .min_by(|(_, a), (_, b)| {
+                (*a - point).mag()
+                    .partial_cmp(&(*b - point).mag())
+                    .unwrap_or(Ordering::Equal)
+            })
+            .map(|(i, _)| i)
+    }
+
+    // Shortest path between the graph vertices nearest `from` and `to`,
+    // as a polyline, via A* with the straight-line distance heuristic.
+    // `cost` optionally scales the weight of each traversed edge (e.g.
+    // to prefer highways over local streets); pass `None` for plain
+    // Euclidean weights.
+    pub fn route(
+        &self,
+        from: Vec2,
+        to: Vec2,
+        cost: Option<&dyn Fn(usize, usize) -> f32>,
+    ) -> Option<Vec<Vec3>> {
+        let start = self.nearest_vertex_2d(from)?;
+        let goal = self.nearest_vertex_2d(to)?;
+
+        self.astar(start, goal, cost)
+    }
+
+    fn astar(
+        &self,
+        start: usize,
+        goal: usize,
+        cost: Option<&dyn Fn(usize, usize) -> f32>,
+    ) -> Option<Vec<Vec3>> {
+        let heuristic = |v: usize| (self.vertices[v] - self.vertices[goal]).mag();
+
+        let mut open = BinaryHeap::new();
+        open.push(OpenEntry { vertex: start, f_score: heuristic(start) });
+
+        let mut came_from: HashMap<usize, usize> = HashMap::new();
+        let mut g_score: HashMap<usize, f32> = HashMap::new();
+        g_score.insert(start, 0.0);
+
+        while let Some(OpenEntry { vertex, .. }) = open.pop() {
+            if vertex == goal {
+                return Some(self.reconstruct_path(&came_from, goal));
+            }
+
+            let current_g = g_score[&vertex];
+
+            for neighbor in self.neighbors(vertex) {
+                let multiplier = cost.map_or(1.0, |f| f(vertex, neighbor));
+                let weight = (self.vertices[neighbor] - self.vertices[vertex]).mag()
+                    * multiplier;
+                let tentative_g = current_g + weight;
+
+                if tentative_g < *g_score.get(&neighbor).unwrap_or(&std::f32::INFINITY) {
+                    came_from.insert(neighbor, vertex);
+                    g_score.insert(neighbor, tentative_g);
+                    open.push(OpenEntry {
+                        vertex: neighbor,
+                        f_score: tentative_g + heuristic(neighbor),
+                    });
+                }
+            }
+        }
+
+        None
+    }
+
+    fn reconstruct_path(
+        &self,
+        came_from: &HashMap<usize, usize>,
+        goal: usize,
+    ) -> Vec<Vec3> {
+        let mut path = vec![self.vertices[goal]];
+        let mut current = goal;
+
+        while let Some(&prev) = came_from.get(&current) {
+            path.push(self.vertices[prev]);
+            current = prev;
+        }
+
+        path.reverse();
+        path
+    }
+}
+
+// Min-heap entry for `RoadGraph::astar`, ordered by ascending f-score
+#[derive(Copy, Clone)]
+struct OpenEntry {
+    vertex: usize,
+    f_score: f32,
+}
+
+impl PartialEq for OpenEntry {
+    fn eq(&self, other: &OpenEntry) -> bool {
+        self.f_score == other.f_score
+    }
+}
+
+impl Eq for OpenEntry {}
+
+impl PartialOrd for OpenEntry {
+    fn partial_cmp(&self, other: &OpenEntry) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OpenEntry {
+    fn cmp(&self, other: &OpenEntry) -> Ordering {
+        other.f_score.partial_cmp(&self.f_score).unwrap_or(Ordering::Equal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `Vec3` isn't known to implement `PartialEq`/`Debug`, so compare
+    // paths by distance instead of with `assert_eq!`
+    fn assert_path_eq(path: &[Vec3], expected: &[Vec3]) {
+        assert_eq!(path.len(), expected.len());
+        for (a, b) in path.iter().zip(expected.iter()) {
+            assert!((*a - *b).mag() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn route_follows_the_only_path_through_a_bend() {
+        let mut graph = RoadGraph::with_capacity(4);
+        graph.connect(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        graph.connect(Vec3::new(1.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 1.0));
+
+        let path = graph.route(Vec2::new(0.0, 0.0), Vec2::new(1.0, 1.0), None)
+            .expect("a path should exist between the two ends");
+
+        assert_path_eq(&path, &[
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 1.0),
+        ]);
+    }
+
+    #[test]
+    fn route_prefers_the_cheaper_of_two_paths() {
+        let mut graph = RoadGraph::with_capacity(4);
+        // Direct edge, expensive per the cost function below
+        graph.connect(Vec3::new(0.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0));
+        // Longer detour, cheap per the cost function below
+        graph.connect(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        graph.connect(Vec3::new(0.0, 0.0, 1.0), Vec3::new(2.0, 0.0, 0.0));
+
+        let cost = |a: usize, b: usize| -> f32 {
+            let direct = (a == 0 && b == 1) || (a == 1 && b == 0);
+            if direct { 10.0 } else { 1.0 }
+        };
+
+        let path = graph.route(
+            Vec2::new(0.0, 0.0), Vec2::new(2.0, 0.0), Some(&cost),
+        ).expect("a path should exist between the two ends");
+
+        assert_path_eq(&path, &[
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(2.0, 0.0, 0.0),
+        ]);
+    }
+
+    #[test]
+    fn route_returns_none_for_an_empty_graph() {
+        let graph = RoadGraph::with_capacity(0);
+        assert!(graph.route(Vec2::new(0.0, 0.0), Vec2::new(1.0, 1.0), None).is_none());
+    }
+}
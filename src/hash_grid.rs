@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+
+use engine::alg::Vec3;
+use engine::graphics::Line;
+
+// Broad-phase uniform grid over the XZ plane, bucketing vertices and
+// edges by the cells they fall in / overlap, so `check_local` only
+// has to test a candidate against nearby segments instead of the
+// whole network
+
+pub struct HashGrid {
+    cell_size: f32,
+    vertex_cells: HashMap<(i32, i32), Vec<usize>>,
+    edge_cells: HashMap<(i32, i32), Vec<usize>>,
+}
+
+impl HashGrid {
+    pub fn new(cell_size: f32) -> HashGrid {
+        HashGrid {
+            cell_size,
+            vertex_cells: HashMap::new(),
+            edge_cells: HashMap::new(),
+        }
+    }
+
+    fn cell_of(&self, x: f32, z: f32) -> (i32, i32) {
+        (
+            (x / self.cell_size).floor() as i32,
+            (z / self.cell_size).floor() as i32,
+        )
+    }
+
+    fn cells_for(&self, line: Line) -> Vec<(i32, i32)> {
+        let (x0, z0) = self.cell_of(
+            line.start.x.min(line.end.x),
+            line.start.z.min(line.end.z),
+        );
+        let (x1, z1) = self.cell_of(
+            line.start.x.max(line.end.x),
+            line.start.z.max(line.end.z),
+        );
+
+        let mut cells = Vec::with_capacity(
+            ((x1 - x0 + 1) * (z1 - z0 + 1)).max(1) as usize
+        );
+
+        for x in x0..=x1 {
+            for z in z0..=z1 {
+                cells.push((x, z));
+            }
+        }
+
+        cells
+    }
+
+    // Register an edge, keyed by index into the owning graph's
+    // `edges`, in every cell its bounding box overlaps
+    pub fn insert_edge(&mut self, edge: usize, line: Line) {
+        for cell in self.cells_for(line) {
+            self.edge_cells.entry(cell).or_insert_with(Vec::new).push(edge);
+        }
+    }
+
+    // Register a vertex, keyed by index into the owning graph's
+    // `vertices`, in the cell it falls in
+    pub fn insert_vertex(&mut self, vertex: usize, point: Vec3) {
+        let cell = self.cell_of(point.x, point.z);
+        self.vertex_cells.entry(cell).or_insert_with(Vec::new).push(vertex);
+    }
+
+    // Edge indices sharing a cell with `line`'s bounding box,
+    // deduplicated
+    pub fn nearby_edges(&self, line: Line) -> Vec<usize> {
+        let mut found: Vec<usize> = self.cells_for(line).into_iter()
+            .filter_map(|cell| self.edge_cells.get(&cell))
+            .flatten()
+            .cloned()
+            .collect();
+
+        found.sort_unstable();
+        found.dedup();
+
+        found
+    }
+
+    // Vertex indices in the 3x3 neighborhood of `point`'s cell,
+    // deduplicated; the neighborhood guards against vertices across a
+    // cell boundary still within a small snap radius
+    pub fn nearby_vertices(&self, point: Vec3) -> Vec<usize> {
+        let (cx, cz) = self.cell_of(point.x, point.z);
+
+        let mut found: Vec<usize> = (-1..=1)
+            .flat_map(|dx| (-1..=1).map(move |dz| (dx, dz)))
+            .filter_map(|(dx, dz)| self.vertex_cells.get(&(cx + dx, cz + dz)))
+            .flatten()
+            .cloned()
+            .collect();
+
+        found.sort_unstable();
+        found.dedup();
+
+        found
+    }
+}
@@ -5,6 +5,29 @@ use self::engine::graphics::*;
 use self::engine::components::*;
 
 extern crate rand;
+use rand::Rng;
+
+mod field2d;
+
+mod graph;
+use graph::RoadGraph;
+
+mod density;
+use density::DensityField;
+
+mod hash_grid;
+use hash_grid::HashGrid;
+
+mod terrain;
+use terrain::{Elevation, HeightField};
+
+mod workers;
+use workers::WorkerPool;
+
+use std::sync::Arc;
+
+// Grid cell size, tuned to roughly the mean road segment length
+const CELL_SIZE: f32 = 0.5;
 
 default_traits!(App, [engine::FixedUpdate, components::softbody::Iterate]);
 
@@ -15,32 +38,298 @@ macro_rules! expand_container {
     }
 }
 
-// Assume 2D segment with y = 0
-fn intersects(a: Line, b: Line) -> bool {
-    let compare = a.end - a.start;
+// Distance within which a new endpoint snaps onto an existing vertex
+// instead of producing a separate, disconnected one
+const SNAP_RADIUS: f32 = 0.05;
+
+// Tests the XZ projection of both segments, so the broad-phase grid
+// and crossing test stay 2D even though segments carry real elevation.
+// Returns the crossing point (with `a`'s real, non-projected Y
+// interpolated back in) and the parametric t along `a`, so callers
+// can find the closest of several candidate intersections and
+// truncate `a` there.
+fn intersects(a: Line, b: Line) -> Option<(Vec3, f32)> {
+    let flatten = |v: Vec3| Vec3::new(v.x, 0.0, v.z);
+
+    let r = flatten(a.end) - flatten(a.start);
+    let s = flatten(b.end) - flatten(b.start);
+
+    let denom = r.cross(s).y;
+    if denom.abs() < std::f32::EPSILON { return None }
+
+    let diff = flatten(b.start) - flatten(a.start);
+    let t = diff.cross(s).y / denom;
+    let u = diff.cross(r).y / denom;
+
+    if t > 0.0 && t < 1.0 && u > 0.0 && u < 1.0 {
+        Some((a.start + (a.end - a.start) * t, t))
+    } else {
+        None
+    }
+}
+
+// Parametric position of `point`'s projection onto `line`, in the XZ
+// plane: 0 at `line.start`, 1 at `line.end`. Used to compare a
+// vertex-snap against a mid-span crossing along the same candidate, so
+// `check_local` can resolve whichever comes first.
+fn param_t(line: Line, point: Vec3) -> f32 {
+    let dx = line.end.x - line.start.x;
+    let dz = line.end.z - line.start.z;
+    let len_sq = dx * dx + dz * dz;
+
+    if len_sq < std::f32::EPSILON { return 0.0 }
 
-    let vs = b.start - a.start;
-    let o1 = vs.cross(compare).y;
-    let vs = b.end - a.start;
-    let o2 = vs.cross(compare).y;
+    ((point.x - line.start.x) * dx + (point.z - line.start.z) * dz) / len_sq
+}
 
-    let compare = b.end - b.start;
+// Result of resolving a candidate segment against the existing network
+struct LocalResult {
+    segment: Line,
 
-    let vs = a.start - b.start;
-    let t1 = vs.cross(compare).y;
-    let vs = a.end - b.start;
-    let t2 = vs.cross(compare).y;
+    // Set when the segment was snapped to an existing vertex or
+    // truncated at an intersection, meaning the branch should not
+    // continue growing past this point
+    terminal: bool,
 
-    o1 * o2 < 0.0 && t1 * t2 < 0.0
+    // Set when the segment was truncated at a mid-span crossing (as
+    // opposed to an endpoint snap), naming the edge that was crossed so
+    // the caller can split it and form a real intersection
+    split_edge: Option<usize>,
 }
 
-fn new_segment(road: Road, query: Query) -> Line {
+// Snap-and-merge local constraints: relocate the candidate's endpoint
+// onto a nearby vertex, or truncate it at the nearest crossing with an
+// existing edge, so accepted segments form a connected graph rather
+// than a tree. When both a snap and a crossing are possible, whichever
+// lies closer to `candidate.start` wins — a crossing nearer than the
+// snap must truncate the candidate there, or it would silently pass
+// straight over the crossed edge on its way to the snap vertex.
+fn check_local(
+    candidate: Line,
+    graph: &RoadGraph,
+    grid: &HashGrid,
+) -> Option<LocalResult> {
+    if (candidate.end - candidate.start).mag() < std::f32::EPSILON {
+        return None;
+    }
+
+    let nearby_vertices = grid.nearby_vertices(candidate.end);
+    let snap = graph.nearest_vertex_among(
+        nearby_vertices, candidate.end, SNAP_RADIUS,
+    ).map(|i| {
+        let point = graph.vertices[i];
+        (point, param_t(candidate, point))
+    });
+
+    let mut crossing: Option<(Vec3, f32, usize)> = None;
+    for edge_index in grid.nearby_edges(candidate) {
+        let edge = graph.edges[edge_index];
+        if let Some((point, t)) = intersects(candidate, graph.line(edge)) {
+            let closer = match crossing {
+                Some((_, closest_t, _)) => t < closest_t,
+                None => true,
+            };
+
+            if closer { crossing = Some((point, t, edge_index)); }
+        }
+    }
+
+    let crossing_first = match (snap, crossing) {
+        (Some((_, snap_t)), Some((_, crossing_t, _))) => crossing_t < snap_t,
+        (None, Some(_)) => true,
+        _ => false,
+    };
+
+    if crossing_first {
+        let (point, _, edge_index) = crossing.unwrap();
+        let truncated = Line::new(candidate.start, point);
+        if (truncated.end - truncated.start).mag() < std::f32::EPSILON {
+            return None;
+        }
+
+        return Some(LocalResult {
+            segment: truncated,
+            terminal: true,
+            split_edge: Some(edge_index),
+        });
+    }
+
+    if let Some((point, _)) = snap {
+        let snapped = Line::new(candidate.start, point);
+        if (snapped.end - snapped.start).mag() < std::f32::EPSILON {
+            return None;
+        }
+
+        return Some(LocalResult { segment: snapped, terminal: true, split_edge: None });
+    }
+
+    Some(LocalResult { segment: candidate, terminal: false, split_edge: None })
+}
+
+// Intersect the ray from the camera through a screen-space point with
+// the y = 0 ground plane, used by the routing demo to turn a mouse
+// click into a world-space point
+fn pick_ground(
+    camera_position: Vec3,
+    camera_orientation: Quat,
+    fov: f32,
+    screen: &ScreenData,
+    screen_point: Vec2,
+) -> Option<Vec3> {
+    let ndc_x = (screen_point.x / screen.width as f32) * 2.0 - 1.0;
+    let ndc_y = 1.0 - (screen_point.y / screen.height as f32) * 2.0;
+
+    let half_fov = fov * std::f32::consts::PI / 360.0;
+    let aspect = screen.width as f32 / screen.height as f32;
+
+    let view_dir = Vec3::new(
+        ndc_x * half_fov.tan() * aspect,
+        ndc_y * half_fov.tan(),
+        1.0,
+    ).norm();
+
+    let world_dir = camera_orientation * view_dir;
+    if world_dir.y.abs() < std::f32::EPSILON { return None }
+
+    let t = -camera_position.y / world_dir.y;
+    if t < 0.0 { return None }
+
+    Some(camera_position + world_dir * t)
+}
+
+fn new_segment(road: Road, query: Query, terrain: &HeightField) -> Line {
     let start = query.origin;
     let end = road.end(query);
 
     Line::new(
-        Vec3::new(start.x, 0.0, start.y),
-        Vec3::new(end.x, 0.0, end.y),
+        Vec3::new(start.x, terrain.sample(start), start.y),
+        Vec3::new(end.x, terrain.sample(end), end.y),
+    )
+}
+
+// Rotated offsets (degrees) tried around the desired bearing when
+// steering towards denser cells
+const DENSITY_FAN: [f32; 4] = [-30.0, -15.0, 15.0, 30.0];
+
+// Below this sampled density, side-branch query `c` is suppressed
+const BRANCH_DENSITY_THRESHOLD: f32 = 0.3;
+
+// Maximum |rise / run| a road is allowed to climb or descend
+const MAX_GRADE: f32 = 0.35;
+
+// Rotated offsets (degrees) tried, in order of increasing deviation
+// from the desired bearing, when a candidate is too steep and needs
+// to follow a contour instead
+const GRADE_FAN: [f32; 6] = [-15.0, 15.0, -30.0, 30.0, -45.0, 45.0];
+
+fn grade(terrain: &HeightField, query: Query, road: Road) -> f32 {
+    let origin_h = terrain.sample(query.origin);
+    let end_h = terrain.sample(road.end(query));
+
+    (end_h - origin_h).abs() / road.length.max(std::f32::EPSILON)
+}
+
+// Global-goals stage: given an accepted segment, propose the queries
+// that extend the network past it (one continuation, two perpendicular
+// branches), per the classic road-generation algorithm. The
+// continuation is steered towards, and shortened within, dense cells
+// of `density`; the `c` branch only fires where density clears
+// `BRANCH_DENSITY_THRESHOLD`. If the steered direction is too steep
+// for `terrain`, a nearby contour-following direction is substituted,
+// or the continuation is dropped if none qualifies.
+fn gen_global(
+    timer: usize,
+    lifetime: usize,
+    road: Road,
+    query: Query,
+    density: &DensityField,
+    terrain: &HeightField,
+) -> (RoadQuery, RoadQuery, RoadQuery) {
+    const MAX_LIFETIME: usize = 8;
+    const BRANCH_LENGTH: f32 = 0.35;
+    const BRANCH_PROBABILITY: f32 = 0.4;
+    const JITTER: f32 = 5.0;
+    const MIN_LENGTH_SCALE: f32 = 0.4;
+
+    let mut rng = rand::thread_rng();
+
+    // Pick the candidate direction (among the desired bearing and a
+    // fan of rotations around it) whose endpoint sits in the densest
+    // cell
+    let mut best_angle = road.angle;
+    let mut best_density = density.sample(road.end(query));
+
+    for offset in DENSITY_FAN.iter() {
+        let angle = road.angle + offset;
+        let candidate = Road { angle, length: road.length };
+        let value = density.sample(candidate.end(query));
+
+        if value > best_density {
+            best_angle = angle;
+            best_density = value;
+        }
+    }
+
+    // Shorten segments in dense cells
+    let length = road.length
+        * (1.0 - best_density * (1.0 - MIN_LENGTH_SCALE));
+
+    // Enforce the grade limit, substituting a nearby contour-following
+    // direction (or dropping the continuation) if the steered one is
+    // too steep
+    let mut graded = Some(Road { angle: best_angle, length });
+
+    if grade(terrain, query, graded.unwrap()) > MAX_GRADE {
+        graded = GRADE_FAN.iter()
+            .map(|offset| Road { angle: best_angle + offset, length })
+            .find(|candidate| grade(terrain, query, *candidate) <= MAX_GRADE);
+    }
+
+    let grade_ok = graded.is_some();
+    let steered = graded.unwrap_or(Road { angle: best_angle, length });
+
+    let end = steered.end(query);
+    let next_angle = query.prev_angle
+        + steered.angle * std::f32::consts::PI / 180.0;
+
+    let next_query = Query {
+        origin: end,
+        prev_angle: next_angle,
+    };
+
+    let straight = RoadQuery {
+        timer: timer + 1,
+        lifetime: lifetime + 1,
+        road: Road {
+            angle: rng.gen_range(-JITTER, JITTER),
+            length,
+        },
+        query: next_query,
+        valid: grade_ok && lifetime < MAX_LIFETIME,
+    };
+
+    // Branches inherit `next_query`'s origin, which falls back to the
+    // rejected, over-the-limit position when no contour direction
+    // qualified for the continuation, so they're only valid if the
+    // continuation's own grade check passed and their own endpoint
+    // also respects the limit
+    let branch = |angle: f32, gate: bool| {
+        let road = Road { angle, length: BRANCH_LENGTH };
+        let branch_grade_ok = grade_ok && grade(terrain, next_query, road) <= MAX_GRADE;
+
+        RoadQuery {
+            timer: timer + 1,
+            lifetime: 0,
+            road,
+            query: next_query,
+            valid: branch_grade_ok && gate && rng.gen::<f32>() < BRANCH_PROBABILITY,
+        }
+    };
+
+    (
+        straight,
+        branch(90.0, true),
+        branch(-90.0, best_density > BRANCH_DENSITY_THRESHOLD),
     )
 }
 
@@ -115,7 +404,22 @@ struct App {
     /* City-gen params */
 
     q: std::collections::BinaryHeap<RoadQuery>,
-    lines: Vec<Line>,
+    graph: RoadGraph,
+    grid: HashGrid,
+    density: Arc<DensityField>,
+    terrain: Arc<HeightField>,
+    workers: WorkerPool,
+
+    // Density sampled, and height-reference state classified, when
+    // each edge was generated; parallel to `graph.edges` and used to
+    // tint the debug overlay
+    densities: Vec<f32>,
+    elevations: Vec<Elevation>,
+
+    /* Routing demo */
+
+    route_from: Option<Vec3>,
+    route: Option<Vec<Vec3>>,
 }
 
 impl engine::Start for App {
@@ -160,29 +464,94 @@ fn start(
     /* City-gen algorithm */
 
     while !self.q.is_empty() {
-        let rq = self.q.pop().unwrap();
-
-        // Check local constraints
-        if !check_local(rq, &self.lines) { continue }
-
-        // Add real segment
-        self.lines.push(
-            // Compute real segment from query
-            new_segment(rq.road, rq.query)
-        );
-
-        // Generate road queries
-        let (a, b, c) = gen_global(
-            rq.timer,
-            rq.lifetime,
-            rq.road,
-            rq.query,
-        );
-
-        // Add road queries back to q
-        self.q.push(a);
-        self.q.push(b);
-        self.q.push(c);
+        // Gather every query at the current (lowest) timer into one
+        // tier: the heap's Ord already guarantees it pops in timer
+        // order, so this is a contiguous batch
+        let batch_timer = self.q.peek().unwrap().timer;
+        let mut batch = Vec::new();
+
+        while let Some(rq) = self.q.peek() {
+            if rq.timer != batch_timer { break }
+            batch.push(self.q.pop().unwrap());
+        }
+
+        // Dispatch the tier's pure global-goals computation to the
+        // worker pool, in parallel. Each dispatched job is tagged with
+        // its batch index so results can be matched back to the query
+        // that produced them regardless of which worker replies first.
+        let dispatched: Vec<usize> = batch.iter().enumerate()
+            .filter(|(_, rq)| rq.valid)
+            .map(|(i, _)| i)
+            .collect();
+
+        for &i in &dispatched {
+            let rq = &batch[i];
+            self.workers.dispatch(i, rq.timer, rq.lifetime, rq.road, rq.query);
+        }
+
+        // All of this tier's jobs were dispatched before any of its
+        // children are consumed below, so this collect is the
+        // synchronization barrier between tiers
+        let mut children = self.workers.collect(dispatched.len());
+
+        // Apply local constraints and merge serially, preserving
+        // batch order, to keep the graph consistent
+        for (i, rq) in batch.into_iter().enumerate() {
+            if !rq.valid { continue }
+            let (a, b, c) = children.remove(&i)
+                .expect("missing worker result for a dispatched query");
+
+            // Compute candidate segment from query
+            let candidate = new_segment(rq.road, rq.query, &self.terrain);
+
+            // Check local constraints: snap to or truncate against
+            // the existing network, using the grid to only test
+            // nearby segments
+            let local = match check_local(candidate, &self.graph, &self.grid) {
+                Some(local) => local,
+                None => continue,
+            };
+
+            // Add real segment, merging vertices as needed
+            let vertices_before = self.graph.vertices.len();
+            let edges_before = self.graph.edges.len();
+
+            let connected = self.graph.connect(local.segment.start, local.segment.end);
+            self.densities.push(self.density.sample(rq.query.origin));
+            self.elevations.push(
+                terrain::classify(connected.line.end.y - connected.line.start.y)
+            );
+
+            for i in vertices_before..self.graph.vertices.len() {
+                self.grid.insert_vertex(i, self.graph.vertices[i]);
+            }
+            for i in edges_before..self.graph.edges.len() {
+                self.grid.insert_edge(i, self.graph.line(self.graph.edges[i]));
+            }
+
+            // A mid-span crossing truncated the candidate: split the
+            // crossed edge at the new vertex so it becomes a real
+            // intersection rather than a degree-one dead end
+            if let Some(crossed) = local.split_edge {
+                let (a, b) = self.graph.split_edge(crossed, connected.end);
+
+                // The new sub-edge inherits the crossed edge's overlay
+                // entries, keeping them parallel to `graph.edges`
+                self.densities.push(self.densities[a]);
+                self.elevations.push(self.elevations[a]);
+
+                self.grid.insert_edge(a, self.graph.line(self.graph.edges[a]));
+                self.grid.insert_edge(b, self.graph.line(self.graph.edges[b]));
+            }
+
+            // A snap or truncation closes this branch off
+            if local.terminal { continue }
+
+            // Add this query's already-computed children back to q
+            self.q.push(a);
+            self.q.push(b);
+            self.q.push(c);
+        }
     }
 } }
 
@@ -245,15 +614,21 @@ fn update(
 
     debug.clear_lines();
 
-    let line_count = self.lines.len();
-    for (i, line) in self.lines.iter().enumerate() {
-        debug.add_line(
-            *line, Color::cyan()
-                * (
-                    1.0 - (i as f32 / line_count as f32)
-                    + 0.1
-                )
-        );
+    let line_count = self.graph.len();
+    for (i, line) in self.graph.lines().enumerate() {
+        let age = 1.0 - (i as f32 / line_count as f32) + 0.1;
+        let density = self.densities.get(i).cloned().unwrap_or(0.0);
+
+        // Base color by height-reference state (incline/level/decline),
+        // then tint brighter where the road was generated under higher
+        // population density
+        let base = match self.elevations.get(i) {
+            Some(Elevation::Incline) => Color::new(1.0, 0.5, 0.2),
+            Some(Elevation::Decline) => Color::new(0.2, 0.5, 1.0),
+            _ => Color::cyan(),
+        };
+
+        debug.add_line(line, base * age * (0.5 + density));
     }
 
     debug.add_local_axes(
@@ -263,9 +638,54 @@ fn update(
         1.0,
         0.5,
     );
+
+    /* Routing demo: click two ground points to draw the shortest path
+       between them over the network */
+
+    if input.key_pressed(input::Key::Mouse1) {
+        let point = pick_ground(
+            camera_position,
+            camera_orientation,
+            self.fov,
+            &screen,
+            input.mouse_position,
+        );
+
+        if let Some(point) = point {
+            match self.route_from {
+                None => {
+                    self.route_from = Some(point);
+                    self.route = None;
+                }
+                Some(from) => {
+                    self.route = self.graph.route(
+                        Vec2::new(from.x, from.z),
+                        Vec2::new(point.x, point.z),
+                        None,
+                    );
+                    self.route_from = None;
+                }
+            }
+        }
+    }
+
+    if let Some(route) = &self.route {
+        for pair in route.windows(2) {
+            debug.add_line(
+                Line::new(pair[0], pair[1]),
+                Color::magenta(),
+            );
+        }
+    }
 } }
 
 fn main() {
+    let density = Arc::new(DensityField::radial(
+        256, 256, Vec2::new(32.0, 32.0), 0.15,
+    ));
+    let terrain = Arc::new(HeightField::flat(Vec2::new(32.0, 32.0)));
+    let workers = WorkerPool::new(Arc::clone(&density), Arc::clone(&terrain));
+
     let app = App {
         camera: None,
         last_angle: Vec2::zero(),
@@ -275,8 +695,95 @@ fn main() {
 
         q: std::collections::BinaryHeap
             ::with_capacity(1),
-        lines: Vec::with_capacity(1024),
+        graph: RoadGraph::with_capacity(1024),
+        grid: HashGrid::new(CELL_SIZE),
+        density,
+        terrain,
+        workers,
+        densities: Vec::with_capacity(1024),
+        elevations: Vec::with_capacity(1024),
+
+        route_from: None,
+        route: None,
     };
 
     engine::go(vec![], app);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intersects_crossing_segments() {
+        let a = Line::new(Vec3::new(-1.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let b = Line::new(Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 0.0, 1.0));
+
+        let (point, t) = intersects(a, b).expect("segments should cross");
+        assert!((point - Vec3::new(0.0, 0.0, 0.0)).mag() < 1e-5);
+        assert!((t - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn intersects_parallel_segments_dont_cross() {
+        let a = Line::new(Vec3::new(-1.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let b = Line::new(Vec3::new(-1.0, 0.0, 1.0), Vec3::new(1.0, 0.0, 1.0));
+
+        assert!(intersects(a, b).is_none());
+    }
+
+    #[test]
+    fn intersects_non_overlapping_segments_dont_cross() {
+        let a = Line::new(Vec3::new(-1.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let b = Line::new(Vec3::new(5.0, 0.0, -1.0), Vec3::new(5.0, 0.0, 1.0));
+
+        assert!(intersects(a, b).is_none());
+    }
+
+    // A candidate whose far endpoint lands within snap radius of an
+    // existing vertex, but whose straight path there first crosses an
+    // unrelated edge, must truncate at the crossing rather than snap
+    // straight through it
+    #[test]
+    fn check_local_prefers_a_nearer_crossing_over_a_farther_snap() {
+        let mut graph = RoadGraph::with_capacity(4);
+        let mut grid = HashGrid::new(CELL_SIZE);
+
+        let crossed = graph.connect(
+            Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 0.0, 1.0),
+        );
+        grid.insert_edge(0, crossed.line);
+        grid.insert_vertex(crossed.start, graph.vertices[crossed.start]);
+        grid.insert_vertex(crossed.end, graph.vertices[crossed.end]);
+
+        // An isolated vertex the candidate's endpoint should snap to,
+        // if nothing crosses first
+        let snap_vertex = graph.vertex_for(Vec3::new(2.0, 0.0, 0.0));
+        grid.insert_vertex(snap_vertex, graph.vertices[snap_vertex]);
+
+        let candidate = Line::new(Vec3::new(-2.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0));
+        let local = check_local(candidate, &graph, &grid)
+            .expect("candidate should resolve to a truncated segment");
+
+        assert!(local.terminal);
+        assert_eq!(local.split_edge, Some(0));
+        assert!((local.segment.end - Vec3::new(0.0, 0.0, 0.0)).mag() < 1e-5);
+    }
+
+    #[test]
+    fn check_local_snaps_when_no_crossing_intervenes() {
+        let mut graph = RoadGraph::with_capacity(4);
+        let mut grid = HashGrid::new(CELL_SIZE);
+
+        let snap_vertex = graph.vertex_for(Vec3::new(2.0, 0.0, 0.0));
+        grid.insert_vertex(snap_vertex, graph.vertices[snap_vertex]);
+
+        let candidate = Line::new(Vec3::new(-2.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0));
+        let local = check_local(candidate, &graph, &grid)
+            .expect("candidate should resolve to a snapped segment");
+
+        assert!(local.terminal);
+        assert_eq!(local.split_edge, None);
+        assert!((local.segment.end - Vec3::new(2.0, 0.0, 0.0)).mag() < 1e-5);
+    }
+}
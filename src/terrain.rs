@@ -0,0 +1,60 @@
+use engine::alg::Vec2;
+
+use field2d::Field2D;
+
+// Bilinearly-sampled elevation field over the XZ plane, letting roads
+// follow real terrain instead of being pinned to y = 0
+
+pub struct HeightField {
+    field: Field2D,
+
+    // Elevation range the sampled [0, 1] values are scaled into
+    scale: f32,
+}
+
+impl HeightField {
+    pub fn flat(extent: Vec2) -> HeightField {
+        HeightField {
+            field: Field2D::new(1, 1, extent),
+            scale: 0.0,
+        }
+    }
+
+    // Build a field from a grayscale heightmap, row-major, one byte
+    // per pixel
+    pub fn from_grayscale(
+        pixels: &[u8],
+        width: usize,
+        height: usize,
+        extent: Vec2,
+        scale: f32,
+    ) -> HeightField {
+        HeightField {
+            field: Field2D::from_grayscale(pixels, width, height, extent),
+            scale,
+        }
+    }
+
+    // Elevation in world units at a ground-plane point
+    pub fn sample(&self, point: Vec2) -> f32 {
+        self.field.bilinear_sample(point) * self.scale
+    }
+}
+
+// Height-reference state of an accepted road segment, surfaced to the
+// debug overlay
+#[derive(Copy, Clone, PartialEq)]
+pub enum Elevation {
+    Incline,
+    Level,
+    Decline,
+}
+
+// Below this absolute rise (world units), a segment counts as level
+const LEVEL_EPSILON: f32 = 0.01;
+
+pub fn classify(rise: f32) -> Elevation {
+    if rise.abs() < LEVEL_EPSILON { Elevation::Level }
+    else if rise > 0.0 { Elevation::Incline }
+    else { Elevation::Decline }
+}
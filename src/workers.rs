@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+
+use density::DensityField;
+use terrain::HeightField;
+
+use super::{gen_global, Query, Road, RoadQuery};
+
+// Fixed-size pool of worker threads that run the pure, read-only
+// `gen_global` computation off the main thread, modeled on a classic
+// chunk-builder worker pool: the main thread owns the priority queue
+// and the authoritative graph, and only ever applies local constraints
+// and merges itself, serially, once a batch's children come back
+const NUM_WORKERS: usize = 4;
+
+struct Job {
+    // Caller-assigned identity, carried through to the result so a
+    // batch of out-of-order replies can be matched back to the query
+    // that produced each one
+    id: usize,
+
+    timer: usize,
+    lifetime: usize,
+    road: Road,
+    query: Query,
+}
+
+type Children = (RoadQuery, RoadQuery, RoadQuery);
+
+pub struct WorkerPool {
+    job_txs: Vec<mpsc::Sender<Job>>,
+    result_rx: mpsc::Receiver<(usize, Children)>,
+    next_worker: usize,
+}
+
+impl WorkerPool {
+    pub fn new(density: Arc<DensityField>, terrain: Arc<HeightField>) -> WorkerPool {
+        let (result_tx, result_rx) = mpsc::channel();
+        let mut job_txs = Vec::with_capacity(NUM_WORKERS);
+
+        for _ in 0..NUM_WORKERS {
+            let (job_tx, job_rx) = mpsc::channel::<Job>();
+            let result_tx = result_tx.clone();
+            let density = Arc::clone(&density);
+            let terrain = Arc::clone(&terrain);
+
+            thread::spawn(move || {
+                for job in job_rx {
+                    let children = gen_global(
+                        job.timer,
+                        job.lifetime,
+                        job.road,
+                        job.query,
+                        &density,
+                        &terrain,
+                    );
+
+                    if result_tx.send((job.id, children)).is_err() { break }
+                }
+            });
+
+            job_txs.push(job_tx);
+        }
+
+        WorkerPool { job_txs, result_rx, next_worker: 0 }
+    }
+
+    // Dispatch one query's global-goals expansion to the next worker,
+    // round-robin. `id` is echoed back alongside the result so the
+    // caller can match it to the query that produced it, regardless
+    // of which worker finishes first.
+    pub fn dispatch(
+        &mut self,
+        id: usize,
+        timer: usize,
+        lifetime: usize,
+        road: Road,
+        query: Query,
+    ) {
+        let worker = self.next_worker;
+        self.next_worker = (self.next_worker + 1) % self.job_txs.len();
+
+        self.job_txs[worker]
+            .send(Job { id, timer, lifetime, road, query })
+            .expect("worker thread panicked");
+    }
+
+    // Block until `count` dispatched jobs have returned their children,
+    // keyed by the `id` each was dispatched with. Since all `RoadQuery`
+    // items at one timer tier are dispatched before any of their
+    // children are consumed, this doubles as the synchronization
+    // barrier between tiers.
+    pub fn collect(&self, count: usize) -> HashMap<usize, Children> {
+        (0..count)
+            .map(|_| self.result_rx.recv().expect("worker thread panicked"))
+            .collect()
+    }
+}